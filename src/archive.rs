@@ -1,3 +1,7 @@
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
 use std::fmt;
 use std::fs;
 use std::io;
@@ -5,8 +9,10 @@ use std::io::prelude::*;
 use std::iter;
 use std::iter::repeat;
 use std::mem;
+use std::os::unix::fs::symlink;
 use std::os::unix::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::str;
 
 #[repr(C)]
 pub struct OldHeader {
@@ -56,6 +62,50 @@ struct GnuSparseHeader {
     numbytes: [u8; 12],
 }
 
+#[repr(C)]
+struct GnuExtSparseHeader {
+    sparse: [GnuSparseHeader; 21],
+    isextended: [u8; 1],
+    padding: [u8; 7],
+}
+
+#[repr(C)]
+struct UstarHeader {
+    name: [u8; 100],
+    mode: [u8; 8],
+    uid: [u8; 8],
+    gid: [u8; 8],
+    size: [u8; 12],
+    mtime: [u8; 12],
+    chksum: [u8; 8],
+    typeflag: [u8; 1],
+    linkname: [u8; 100],
+    magic: [u8; 6],
+    version: [u8; 2],
+    uname: [u8; 32],
+    gname: [u8; 32],
+    devmajor: [u8; 8],
+    devminor: [u8; 8],
+    prefix: [u8; 155],
+    pad: [u8; 12],
+}
+
+/// Controls how `Archiver` stores paths that don't fit in the 100-byte
+/// `name` field of a ustar header.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum LongNameMode {
+    /// Precede the entry with a GNU `././@LongLink` header carrying the
+    /// full path.
+    Gnu,
+    /// Split the path across the ustar `prefix` and `name` fields,
+    /// falling back to `Gnu` when no valid split exists.
+    Ustar,
+    /// Precede the entry with a PAX extended-header record (typeflag
+    /// `x`) carrying the full path, leaving the ustar `name` field
+    /// holding a truncated fallback.
+    Pax,
+}
+
 #[repr(C)]
 struct Header {
     bytes: [u8; 512],
@@ -79,6 +129,15 @@ impl Header {
         return Ok(());
     }
 
+    /// Overwrites `magic`/`version` with POSIX ustar's (`"ustar\0"` /
+    /// `"00"`), for headers whose `prefix` field holds path data rather
+    /// than GNU's atime/ctime/sparse fields, which occupy the same bytes.
+    fn set_ustar_magic(&mut self) {
+        let ustar = self.as_mut_ustar();
+        ustar.magic = *b"ustar\0";
+        ustar.version = *b"00";
+    }
+
     fn calculate_chksum(&self) -> u32 {
         let old = self.as_old();
         let start = old as *const _ as usize;
@@ -96,49 +155,394 @@ impl Header {
         unsafe { cast_mut(self) }
     }
 
+    fn as_mut_ustar(&mut self) -> &mut UstarHeader {
+        unsafe { cast_mut(self) }
+    }
+
+    fn as_gnu(&self) -> &GnuHeader {
+        unsafe { cast(self) }
+    }
+
+    fn as_ustar(&self) -> &UstarHeader {
+        unsafe { cast(self) }
+    }
+
     fn as_old(&self) -> &OldHeader {
         unsafe { cast(self) }
     }
+
+    fn validate_chksum(&self) -> io::Result<()> {
+        let stored = octal_from(&self.as_old().chksum) as u32;
+        if stored != self.calculate_chksum() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "archive header checksum mismatch",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Controls which metadata `Archiver::add_file` copies from the
+/// filesystem into each entry's header.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum HeaderMode {
+    /// Copy `uid`, `gid`, `mtime`, and `mode` as reported by
+    /// `fs::symlink_metadata` (so a symlink's own metadata, not its target's).
+    Complete,
+    /// Zero out `uid`, `gid`, and `mtime`, and normalize `mode` to 0o755
+    /// for executables and directories or 0o644 otherwise, so that
+    /// archiving the same tree twice produces byte-identical output.
+    Deterministic,
 }
 
 pub struct Archiver<W: Write> {
     obj: Option<W>,
+    longname_mode: LongNameMode,
+    header_mode: HeaderMode,
+    hardlinks: HashMap<(u64, u64), PathBuf>,
+    extra_pax: Vec<(Vec<u8>, Vec<u8>)>,
 }
 
 impl<W: Write> Archiver<W> {
     pub fn new(obj: W) -> Archiver<W> {
-        Archiver { obj: Some(obj) }
+        Archiver {
+            obj: Some(obj),
+            longname_mode: LongNameMode::Gnu,
+            header_mode: HeaderMode::Complete,
+            hardlinks: HashMap::new(),
+            extra_pax: Vec::new(),
+        }
+    }
+
+    /// Selects how paths longer than 100 bytes are stored. Defaults to
+    /// `LongNameMode::Gnu`.
+    pub fn set_longname_mode(&mut self, mode: LongNameMode) {
+        self.longname_mode = mode;
+    }
+
+    /// Selects which metadata is written into each entry's header.
+    /// Defaults to `HeaderMode::Complete`.
+    pub fn mode(&mut self, mode: HeaderMode) {
+        self.header_mode = mode;
+    }
+
+    /// Queues a custom PAX extended-header attribute (e.g.
+    /// `SCHILY.xattr.user.foo`) to be attached to the next entry written,
+    /// whether a file, directory, symlink, or hardlink (for
+    /// `append_dir_all`, that is the entry for `fs_path` itself).
+    pub fn add_pax_extension<K: Into<Vec<u8>>, V: Into<Vec<u8>>>(&mut self, key: K, value: V) {
+        self.extra_pax.push((key.into(), value.into()));
     }
 
     fn inner(&mut self) -> &mut W {
         self.obj.as_mut().unwrap()
     }
 
-    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
-        let meta = fs::metadata(&path)?;
+    fn write_padded(&mut self, data: &[u8]) -> io::Result<()> {
+        self.inner().write_all(data)?;
+        let buf = [0; 512];
+        let remaining = 512 - (data.len() % 512);
+        if remaining < 512 {
+            self.inner().write_all(&buf[..remaining])?;
+        }
+        Ok(())
+    }
+
+    fn add_gnu_long_name(&mut self, name: &[u8]) -> io::Result<()> {
         let mut header = Header::new();
+        copy_into(&mut header.as_mut_gnu().name, b"././@LongLink")?;
+        header.as_mut_gnu().typeflag = *b"L";
+        octal_into(&mut header.as_mut_gnu().size, name.len() + 1);
+        let chksum = header.calculate_chksum();
+        octal_into(&mut header.as_mut_gnu().chksum, chksum);
+        self.inner().write_all(&header.bytes)?;
+
+        let mut data = name.to_vec();
+        data.push(0);
+        self.write_padded(&data)
+    }
+
+    /// Writes a `typeflag == 'x'` PAX extended-header entry whose body
+    /// holds one record per `attrs` pair.
+    fn write_pax_extension(&mut self, attrs: &[(Vec<u8>, Vec<u8>)]) -> io::Result<()> {
+        let mut blob = Vec::new();
+        for (key, value) in attrs {
+            blob.extend(pax_record(key, value));
+        }
 
-        header.set_name(path.as_ref())?;
-        octal_into(&mut header.as_mut_gnu().mode, meta.mode());
-        octal_into(&mut header.as_mut_gnu().uid, meta.uid());
-        octal_into(&mut header.as_mut_gnu().gid, meta.gid());
-        octal_into(
-            &mut header.as_mut_gnu().size,
-            if meta.is_file() { meta.len() } else { 0 },
-        );
-        octal_into(&mut header.as_mut_gnu().mtime, meta.mtime());
+        let mut header = Header::new();
+        copy_into(&mut header.as_mut_gnu().name, b"././@PaxHeader")?;
+        header.as_mut_gnu().typeflag = *b"x";
+        numeric_into(&mut header.as_mut_gnu().size, blob.len() as u64);
+        self.finish_entry(&mut header)?;
+        self.write_padded(&blob)
+    }
 
+    /// Writes a GNU `././@LongLink` preamble (typeflag `K`) carrying the
+    /// full link target, mirroring `add_gnu_long_name`'s handling of `name`.
+    fn add_gnu_long_linkname(&mut self, linkname: &[u8]) -> io::Result<()> {
+        let mut header = Header::new();
+        copy_into(&mut header.as_mut_gnu().name, b"././@LongLink")?;
+        header.as_mut_gnu().typeflag = *b"K";
+        octal_into(&mut header.as_mut_gnu().size, linkname.len() + 1);
         let chksum = header.calculate_chksum();
         octal_into(&mut header.as_mut_gnu().chksum, chksum);
-
         self.inner().write_all(&header.bytes)?;
 
-        let len = if meta.is_file() {
-            let mut contents = fs::File::open(&path)?;
-            io::copy(&mut contents, &mut self.inner())?
+        let mut data = linkname.to_vec();
+        data.push(0);
+        self.write_padded(&data)
+    }
+
+    /// Writes `target` into `header`'s `linkname` field, routing it
+    /// through the same overflow handling `name_header` applies to
+    /// `name`: a GNU long-link preamble, a queued PAX `linkpath` record,
+    /// or (ustar has no `prefix`-style field for `linkname`) the GNU
+    /// preamble as well. `header`'s `linkname` always gets the
+    /// (possibly truncated) fallback, matching `name`'s behavior.
+    fn linkname_header(&mut self, header: &mut Header, target: &[u8]) -> io::Result<()> {
+        if target.len() > 100 {
+            match self.longname_mode {
+                LongNameMode::Gnu | LongNameMode::Ustar => self.add_gnu_long_linkname(target)?,
+                LongNameMode::Pax => self.extra_pax.push((b"linkpath".to_vec(), target.to_vec())),
+            }
+        }
+        copy_into(&mut header.as_mut_gnu().linkname, target)
+    }
+
+    /// Queues the `mtime`/`uid`/`gid` PAX records `meta` requires:
+    /// sub-second `mtime`, and any of `uid`/`gid` that overflow their
+    /// octal fields. Does not touch `size`, since for sparse files the
+    /// stored size is not `meta.len()` (see `queue_pax_size`).
+    fn queue_pax_identity(&mut self, meta: &fs::Metadata) {
+        if self.header_mode == HeaderMode::Complete {
+            let mtime_nsec = meta.mtime_nsec();
+            if mtime_nsec != 0 {
+                let mtime = format!("{}.{:09}", meta.mtime(), mtime_nsec);
+                self.extra_pax.push((b"mtime".to_vec(), mtime.into_bytes()));
+            }
+            if exceeds_octal(meta.uid() as u64, 8) {
+                self.extra_pax.push((b"uid".to_vec(), meta.uid().to_string().into_bytes()));
+            }
+            if exceeds_octal(meta.gid() as u64, 8) {
+                self.extra_pax.push((b"gid".to_vec(), meta.gid().to_string().into_bytes()));
+            }
+        }
+    }
+
+    /// Queues a `size` PAX record when `size` (the number of bytes the
+    /// header's own `size` field will hold) overflows the 12-byte octal
+    /// field. Callers pass the stored size, not the file's logical
+    /// length, so sparse files (whose `size` holds only the data-segment
+    /// total) stay consistent with `add_sparse_file`'s body.
+    fn queue_pax_size(&mut self, size: u64) {
+        if exceeds_octal(size, 12) {
+            self.extra_pax.push((b"size".to_vec(), size.to_string().into_bytes()));
+        }
+    }
+
+    /// Writes a combined PAX extended header for every attribute queued
+    /// so far (via `name_header`, `linkname_header`,
+    /// `queue_pax_identity`, `queue_pax_size`, or `add_pax_extension`), if any.
+    fn flush_pax_extension(&mut self) -> io::Result<()> {
+        if self.extra_pax.is_empty() {
+            return Ok(());
+        }
+        let attrs = mem::take(&mut self.extra_pax);
+        self.write_pax_extension(&attrs)
+    }
+
+    /// Builds a fresh header with `path` written into its name (or
+    /// `././@LongLink`/ustar-`prefix` fields when it doesn't fit). In
+    /// `LongNameMode::Pax`, a `path` record is queued instead, to be
+    /// flushed by `flush_pax_extension`; `name` here just carries the
+    /// truncated fallback.
+    fn name_header(&mut self, path: &Path) -> io::Result<Header> {
+        let mut header = Header::new();
+
+        let name_bytes = path.as_os_str().as_bytes();
+        let mut ustar_split = None;
+        if name_bytes.len() > 100 {
+            match self.longname_mode {
+                LongNameMode::Gnu => self.add_gnu_long_name(name_bytes)?,
+                LongNameMode::Ustar => match split_ustar_path(name_bytes) {
+                    Some(split) => ustar_split = Some(split),
+                    None => self.add_gnu_long_name(name_bytes)?,
+                },
+                LongNameMode::Pax => self.extra_pax.push((b"path".to_vec(), name_bytes.to_vec())),
+            }
+        }
+
+        if let Some((prefix, name)) = ustar_split {
+            let ustar = header.as_mut_ustar();
+            copy_into(&mut ustar.name, name)?;
+            copy_into(&mut ustar.prefix, prefix)?;
+            header.set_ustar_magic();
         } else {
-            0
+            header.set_name(path)?;
+        }
+
+        Ok(header)
+    }
+
+    /// Copies `mode`, `uid`, `gid`, and `mtime` from `meta` into `header`,
+    /// honoring `HeaderMode`.
+    fn apply_metadata(&self, header: &mut Header, meta: &fs::Metadata) {
+        let (mode, uid, gid, mtime) = match self.header_mode {
+            HeaderMode::Complete => (meta.mode(), meta.uid(), meta.gid(), meta.mtime()),
+            HeaderMode::Deterministic => {
+                let canonical = if meta.is_dir() || meta.mode() & 0o111 != 0 {
+                    0o755
+                } else {
+                    0o644
+                };
+                (canonical, 0, 0, 0)
+            }
         };
+        octal_into(&mut header.as_mut_gnu().mode, mode);
+        numeric_into(&mut header.as_mut_gnu().uid, uid as u64);
+        numeric_into(&mut header.as_mut_gnu().gid, gid as u64);
+        numeric_into(&mut header.as_mut_gnu().mtime, mtime as u64);
+    }
+
+    /// Calculates and writes `header`'s checksum, then writes it out.
+    fn finish_entry(&mut self, header: &mut Header) -> io::Result<()> {
+        let chksum = header.calculate_chksum();
+        octal_into(&mut header.as_mut_gnu().chksum, chksum);
+        self.inner().write_all(&header.bytes)
+    }
+
+    /// Adds a symlink entry (typeflag `2`) pointing at its `read_link` target.
+    pub fn add_symlink<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let meta = fs::symlink_metadata(&path)?;
+        self.write_symlink(path.as_ref(), path.as_ref(), &meta)
+    }
+
+    fn write_symlink(&mut self, fs_path: &Path, name: &Path, meta: &fs::Metadata) -> io::Result<()> {
+        let target = fs::read_link(fs_path)?;
+        let target_bytes = target.as_os_str().as_bytes();
+        let mut header = self.name_header(name)?;
+        header.as_mut_gnu().typeflag = *b"2";
+        self.linkname_header(&mut header, target_bytes)?;
+        self.apply_metadata(&mut header, meta);
+        self.queue_pax_identity(meta);
+        numeric_into(&mut header.as_mut_gnu().size, 0);
+        self.flush_pax_extension()?;
+        self.finish_entry(&mut header)
+    }
+
+    /// Adds a directory entry (typeflag `5`) with an empty body.
+    pub fn add_dir<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let meta = fs::symlink_metadata(&path)?;
+        self.write_dir(path.as_ref(), &meta)
+    }
+
+    fn write_dir(&mut self, name: &Path, meta: &fs::Metadata) -> io::Result<()> {
+        let mut header = self.name_header(name)?;
+        header.as_mut_gnu().typeflag = *b"5";
+        self.apply_metadata(&mut header, meta);
+        self.queue_pax_identity(meta);
+        numeric_into(&mut header.as_mut_gnu().size, 0);
+        self.flush_pax_extension()?;
+        self.finish_entry(&mut header)
+    }
+
+    /// Adds a hardlink entry (typeflag `1`) pointing at the first path
+    /// archived with the same `(dev, ino)`.
+    fn write_hardlink(&mut self, name: &Path, meta: &fs::Metadata, target: &Path) -> io::Result<()> {
+        let target_bytes = target.as_os_str().as_bytes();
+        let mut header = self.name_header(name)?;
+        header.as_mut_gnu().typeflag = *b"1";
+        self.linkname_header(&mut header, target_bytes)?;
+        self.apply_metadata(&mut header, meta);
+        self.queue_pax_identity(meta);
+        numeric_into(&mut header.as_mut_gnu().size, 0);
+        self.flush_pax_extension()?;
+        self.finish_entry(&mut header)
+    }
+
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.append_path(path.as_ref(), path.as_ref())
+    }
+
+    /// Recursively archives everything under `fs_path`, storing each entry
+    /// as `archive_path` joined with its path relative to `fs_path`.
+    /// Subdirectories are emitted as directory entries (with a trailing
+    /// `/` on their stored name) before the files they contain, so the
+    /// archive unpacks in a valid order.
+    pub fn append_dir_all<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        archive_path: P,
+        fs_path: Q,
+    ) -> io::Result<()> {
+        self.append_dir_entry(archive_path.as_ref(), fs_path.as_ref())
+    }
+
+    fn append_dir_entry(&mut self, archive_path: &Path, fs_path: &Path) -> io::Result<()> {
+        let meta = fs::symlink_metadata(fs_path)?;
+        if !meta.is_dir() {
+            return self.append_path(fs_path, archive_path);
+        }
+
+        self.write_dir(&with_trailing_slash(archive_path), &meta)?;
+
+        let mut entries = fs::read_dir(fs_path)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let child_name = archive_path.join(entry.file_name());
+            self.append_dir_entry(&child_name, &entry.path())?;
+        }
+        Ok(())
+    }
+
+    /// Archives the file, symlink, directory, or hardlink at `fs_path`,
+    /// storing it under `name` in the archive.
+    fn append_path(&mut self, fs_path: &Path, name: &Path) -> io::Result<()> {
+        let meta = fs::symlink_metadata(fs_path)?;
+
+        if meta.file_type().is_symlink() {
+            return self.write_symlink(fs_path, name, &meta);
+        }
+        if meta.is_dir() {
+            return self.write_dir(name, &meta);
+        }
+
+        let inode = (meta.dev(), meta.ino());
+        if let Some(original) = self.hardlinks.get(&inode).cloned() {
+            return self.write_hardlink(name, &meta, &original);
+        }
+        self.hardlinks.insert(inode, name.to_path_buf());
+
+        let mut header = self.name_header(name)?;
+        self.apply_metadata(&mut header, &meta);
+        self.queue_pax_identity(&meta);
+
+        let sparse = {
+            let file = fs::File::open(fs_path)?;
+            let segments = sparse_segments(&file, meta.len())?;
+            if is_sparse(&segments, meta.len()) {
+                Some((file, segments))
+            } else {
+                None
+            }
+        };
+
+        if let Some((file, segments)) = sparse {
+            let data_len: u64 = segments.iter().map(|s| s.numbytes).sum();
+            self.queue_pax_size(data_len);
+            self.flush_pax_extension()?;
+            return self.add_sparse_file(header, file, meta.len(), &segments);
+        }
+
+        self.queue_pax_size(meta.len());
+        self.flush_pax_extension()?;
+
+        numeric_into(&mut header.as_mut_gnu().size, meta.len());
+        self.finish_entry(&mut header)?;
+
+        let mut contents = fs::File::open(fs_path)?;
+        let len = io::copy(&mut contents, &mut self.inner())?;
 
         let buf = [0; 512];
         let remaining = 512 - (len % 512);
@@ -148,6 +552,65 @@ impl<W: Write> Archiver<W> {
 
         Ok(())
     }
+
+    /// Writes a `typeflag == 'S'` GNU sparse entry, recording `segments`
+    /// (the file's non-hole byte ranges) in the header's `sparse` array
+    /// and any overflow in extended sparse header blocks, then writes
+    /// only the data segments as the body.
+    fn add_sparse_file(
+        &mut self,
+        mut header: Header,
+        mut file: fs::File,
+        file_len: u64,
+        segments: &[SparseSegment],
+    ) -> io::Result<()> {
+        header.as_mut_gnu().typeflag = *b"S";
+
+        let data_len: u64 = segments.iter().map(|s| s.numbytes).sum();
+        numeric_into(&mut header.as_mut_gnu().size, data_len);
+        numeric_into(&mut header.as_mut_gnu().realsize, file_len);
+
+        let in_header = &segments[..cmp::min(segments.len(), 4)];
+        for (slot, seg) in header.as_mut_gnu().sparse.iter_mut().zip(in_header) {
+            numeric_into(&mut slot.offset, seg.offset);
+            numeric_into(&mut slot.numbytes, seg.numbytes);
+        }
+        let mut extended = &segments[in_header.len()..];
+        if !extended.is_empty() {
+            header.as_mut_gnu().isextended = [1];
+        }
+
+        self.finish_entry(&mut header)?;
+
+        while !extended.is_empty() {
+            let chunk_len = cmp::min(extended.len(), 21);
+            let (chunk, rest) = extended.split_at(chunk_len);
+
+            let mut block = [0u8; 512];
+            {
+                let ext: &mut GnuExtSparseHeader = unsafe { cast_mut(&mut block) };
+                for (slot, seg) in ext.sparse.iter_mut().zip(chunk) {
+                    numeric_into(&mut slot.offset, seg.offset);
+                    numeric_into(&mut slot.numbytes, seg.numbytes);
+                }
+                ext.isextended = [if rest.is_empty() { 0 } else { 1 }];
+            }
+            self.inner().write_all(&block)?;
+            extended = rest;
+        }
+
+        for seg in segments {
+            file.seek(io::SeekFrom::Start(seg.offset))?;
+            io::copy(&mut Read::by_ref(&mut file).take(seg.numbytes), &mut self.inner())?;
+        }
+
+        let remaining = 512 - (data_len % 512);
+        if remaining < 512 {
+            self.inner().write_all(&[0; 512][..remaining as usize])?;
+        }
+
+        Ok(())
+    }
 }
 
 impl<W: Write> Drop for Archiver<W> {
@@ -156,6 +619,528 @@ impl<W: Write> Drop for Archiver<W> {
     }
 }
 
+/// The type of filesystem entry recorded in a header's typeflag byte.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EntryType {
+    Regular,
+    Directory,
+    Symlink,
+    HardLink,
+    Other(u8),
+}
+
+impl EntryType {
+    fn from_typeflag(flag: u8) -> EntryType {
+        match flag {
+            0 | b'0' => EntryType::Regular,
+            b'1' => EntryType::HardLink,
+            b'2' => EntryType::Symlink,
+            b'5' => EntryType::Directory,
+            other => EntryType::Other(other),
+        }
+    }
+}
+
+/// A read-only tar archive, parsed entry-by-entry from `obj`.
+pub struct Archive<R: Read> {
+    obj: RefCell<R>,
+    pos: Cell<u64>,
+}
+
+impl<R: Read> Archive<R> {
+    pub fn new(obj: R) -> Archive<R> {
+        Archive {
+            obj: RefCell::new(obj),
+            pos: Cell::new(0),
+        }
+    }
+
+    /// Returns an iterator over the entries of this archive.
+    pub fn entries(&self) -> Entries<'_, R> {
+        Entries {
+            archive: self,
+            next_header_pos: Cell::new(0),
+            done: Cell::new(false),
+        }
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.obj.borrow_mut().read(buf)?;
+        self.pos.set(self.pos.get() + n as u64);
+        Ok(n)
+    }
+
+    fn read_exact(&self, buf: &mut [u8]) -> io::Result<()> {
+        let mut read = 0;
+        while read < buf.len() {
+            let n = self.read(&mut buf[read..])?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "unexpected EOF in archive",
+                ));
+            }
+            read += n;
+        }
+        Ok(())
+    }
+
+    fn skip(&self, mut amt: u64) -> io::Result<()> {
+        let mut buf = [0u8; 4096];
+        while amt > 0 {
+            let n = cmp::min(amt, buf.len() as u64) as usize;
+            self.read_exact(&mut buf[..n])?;
+            amt -= n as u64;
+        }
+        Ok(())
+    }
+}
+
+/// An iterator over the entries of an `Archive`.
+pub struct Entries<'a, R: Read + 'a> {
+    archive: &'a Archive<R>,
+    next_header_pos: Cell<u64>,
+    done: Cell<bool>,
+}
+
+impl<'a, R: Read> Entries<'a, R> {
+    /// Reads the next header block, transparently consuming any GNU `L`/`K`
+    /// (`././@LongLink`) or PAX `x` (`././@PaxHeader`) preamble entries
+    /// that precede it and folding their overrides (`path`/`linkpath`,
+    /// and for PAX also `size`) into the real entry that follows.
+    fn next_entry(&mut self) -> io::Result<Option<Entry<'a, R>>> {
+        let mut pending_name: Option<Vec<u8>> = None;
+        let mut pending_linkname: Option<Vec<u8>> = None;
+        let mut pending_size: Option<u64> = None;
+
+        loop {
+            let to_skip = self
+                .next_header_pos
+                .get()
+                .saturating_sub(self.archive.pos.get());
+            if to_skip > 0 {
+                self.archive.skip(to_skip)?;
+            }
+
+            let mut block = [0; 512];
+            self.archive.read_exact(&mut block)?;
+            if is_zero_block(&block) {
+                let mut trailer = [0; 512];
+                let _ = self.archive.read_exact(&mut trailer);
+                return Ok(None);
+            }
+
+            let header = Header { bytes: block };
+            header.validate_chksum()?;
+
+            let raw_size = octal_from(&header.as_gnu().size);
+            self.next_header_pos
+                .set(self.archive.pos.get() + round_up_to_512(raw_size));
+
+            match header.as_gnu().typeflag[0] {
+                b'L' => {
+                    let data = self.read_preamble_body(raw_size)?;
+                    pending_name = Some(trim_trailing_nul(&data).to_vec());
+                    continue;
+                }
+                b'K' => {
+                    let data = self.read_preamble_body(raw_size)?;
+                    pending_linkname = Some(trim_trailing_nul(&data).to_vec());
+                    continue;
+                }
+                b'x' => {
+                    let data = self.read_preamble_body(raw_size)?;
+                    for (key, value) in parse_pax_records(&data) {
+                        match key.as_slice() {
+                            b"path" => pending_name = Some(value),
+                            b"linkpath" => pending_linkname = Some(value),
+                            b"size" => {
+                                if let Ok(size) = str::from_utf8(&value).unwrap_or("").parse() {
+                                    pending_size = Some(size);
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    continue;
+                }
+                b'S' => {
+                    let sparse = self.read_gnu_sparse(&header)?;
+                    let size = pending_size.unwrap_or(raw_size);
+                    self.next_header_pos
+                        .set(self.archive.pos.get() + round_up_to_512(size));
+
+                    return Ok(Some(Entry {
+                        archive: self.archive,
+                        header,
+                        size: sparse.realsize,
+                        remaining: Cell::new(size),
+                        pax_path: pending_name.map(|name| PathBuf::from(OsStr::from_bytes(&name))),
+                        pax_linkname: pending_linkname,
+                        sparse: Some(sparse),
+                        logical_pos: Cell::new(0),
+                        seg_idx: Cell::new(0),
+                    }));
+                }
+                _ => {
+                    let size = pending_size.unwrap_or(raw_size);
+                    self.next_header_pos
+                        .set(self.archive.pos.get() + round_up_to_512(size));
+
+                    return Ok(Some(Entry {
+                        archive: self.archive,
+                        header,
+                        size,
+                        remaining: Cell::new(size),
+                        pax_path: pending_name.map(|name| PathBuf::from(OsStr::from_bytes(&name))),
+                        pax_linkname: pending_linkname,
+                        sparse: None,
+                        logical_pos: Cell::new(0),
+                        seg_idx: Cell::new(0),
+                    }));
+                }
+            }
+        }
+    }
+
+    /// Reads a preamble entry's `size`-byte body, skipping the padding up
+    /// to the next 512-byte boundary.
+    fn read_preamble_body(&self, size: u64) -> io::Result<Vec<u8>> {
+        let mut data = vec![0u8; round_up_to_512(size) as usize];
+        self.archive.read_exact(&mut data)?;
+        data.truncate(size as usize);
+        Ok(data)
+    }
+
+    /// Reads a GNU sparse (`typeflag == 'S'`) header's `sparse[4]` array and
+    /// any overflow extended sparse header blocks that immediately follow
+    /// it (consuming them from the stream), returning the full segment map
+    /// and logical `realsize`.
+    fn read_gnu_sparse(&self, header: &Header) -> io::Result<SparseMap> {
+        let gnu = header.as_gnu();
+        let realsize = octal_from(&gnu.realsize);
+        let mut segments: Vec<(u64, u64)> = gnu
+            .sparse
+            .iter()
+            .map(|s| (octal_from(&s.offset), octal_from(&s.numbytes)))
+            .filter(|&(_, numbytes)| numbytes != 0)
+            .collect();
+
+        let mut extended = gnu.isextended[0] != 0;
+        while extended {
+            let mut block = [0u8; 512];
+            self.archive.read_exact(&mut block)?;
+            let ext: &GnuExtSparseHeader = unsafe { cast(&block) };
+            for s in ext.sparse.iter() {
+                let numbytes = octal_from(&s.numbytes);
+                if numbytes != 0 {
+                    segments.push((octal_from(&s.offset), numbytes));
+                }
+            }
+            extended = ext.isextended[0] != 0;
+        }
+
+        Ok(SparseMap { segments, realsize })
+    }
+}
+
+/// Parses a PAX extended-header blob (records of the form
+/// `"<len> <key>=<value>\n"`) into key/value pairs.
+fn parse_pax_records(blob: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut records = Vec::new();
+    let mut rest = blob;
+    while !rest.is_empty() {
+        let space = match rest.iter().position(|&b| b == b' ') {
+            Some(i) => i,
+            None => break,
+        };
+        let len: usize = match str::from_utf8(&rest[..space]).ok().and_then(|s| s.parse().ok()) {
+            Some(len) if len > space && len <= rest.len() => len,
+            _ => break,
+        };
+        let body = &rest[space + 1..len - 1];
+        if let Some(eq) = body.iter().position(|&b| b == b'=') {
+            records.push((body[..eq].to_vec(), body[eq + 1..].to_vec()));
+        }
+        rest = &rest[len..];
+    }
+    records
+}
+
+impl<'a, R: Read> Iterator for Entries<'a, R> {
+    type Item = io::Result<Entry<'a, R>>;
+
+    fn next(&mut self) -> Option<io::Result<Entry<'a, R>>> {
+        if self.done.get() {
+            return None;
+        }
+        match self.next_entry() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.done.set(true);
+                None
+            }
+            Err(e) => {
+                self.done.set(true);
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// A GNU sparse (`typeflag == 'S'`) entry's data-segment map: `segments`
+/// are the `(offset, numbytes)` runs of non-hole bytes actually stored in
+/// the archive body, in ascending order, and `realsize` is the full
+/// logical file length those segments (and the holes between them)
+/// reconstruct to.
+struct SparseMap {
+    segments: Vec<(u64, u64)>,
+    realsize: u64,
+}
+
+/// A single entry of an `Archive`, readable for exactly its content bytes.
+pub struct Entry<'a, R: Read + 'a> {
+    archive: &'a Archive<R>,
+    header: Header,
+    size: u64,
+    remaining: Cell<u64>,
+    /// `path` PAX override, or the GNU long name, queued by a preamble
+    /// entry that preceded this one.
+    pax_path: Option<PathBuf>,
+    /// `linkpath` PAX override queued by a preamble entry.
+    pax_linkname: Option<Vec<u8>>,
+    /// This entry's sparse segment map, for `typeflag == 'S'` entries.
+    /// `Read` reconstructs the logical layout from this rather than
+    /// copying `remaining` data bytes straight through.
+    sparse: Option<SparseMap>,
+    /// Read progress in logical (post-reconstruction) byte space, and the
+    /// index of the next segment in `sparse` that hasn't been fully read.
+    /// Unused for non-sparse entries.
+    logical_pos: Cell<u64>,
+    seg_idx: Cell<usize>,
+}
+
+impl<'a, R: Read> Entry<'a, R> {
+    pub fn path(&self) -> PathBuf {
+        if let Some(path) = &self.pax_path {
+            return path.clone();
+        }
+
+        let name = trim_trailing_nul(&self.header.as_gnu().name);
+        let prefix = trim_trailing_nul(&self.header.as_ustar().prefix);
+        if prefix.is_empty() {
+            PathBuf::from(OsStr::from_bytes(name))
+        } else {
+            let mut full = prefix.to_vec();
+            full.push(b'/');
+            full.extend_from_slice(name);
+            PathBuf::from(OsStr::from_bytes(&full))
+        }
+    }
+
+    /// The symlink or hardlink target this entry points at, if any.
+    pub fn linkname(&self) -> Option<PathBuf> {
+        if let Some(linkname) = &self.pax_linkname {
+            return Some(PathBuf::from(OsStr::from_bytes(linkname)));
+        }
+
+        let linkname = trim_trailing_nul(&self.header.as_gnu().linkname);
+        if linkname.is_empty() {
+            None
+        } else {
+            Some(PathBuf::from(OsStr::from_bytes(linkname)))
+        }
+    }
+
+    /// The entry's content length: the number of bytes `Read` yields,
+    /// which for a sparse entry is the reconstructed logical file length
+    /// (`realsize`), not the (smaller) number of bytes actually stored in
+    /// the archive body.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn mode(&self) -> u32 {
+        octal_from(&self.header.as_gnu().mode) as u32
+    }
+
+    pub fn mtime(&self) -> u64 {
+        octal_from(&self.header.as_gnu().mtime)
+    }
+
+    pub fn entry_type(&self) -> EntryType {
+        EntryType::from_typeflag(self.header.as_gnu().typeflag[0])
+    }
+
+    /// Writes this entry to `dst`, creating a directory, symlink, or
+    /// hardlink as appropriate for `entry_type()`, restoring `mode` for
+    /// regular files and directories.
+    pub fn unpack<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<()> {
+        let dst = dst.as_ref();
+        match self.entry_type() {
+            EntryType::Directory => {
+                fs::create_dir_all(dst)?;
+                fs::set_permissions(dst, fs::Permissions::from_mode(self.mode()))
+            }
+            EntryType::Symlink => {
+                let target = self.linkname().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "symlink entry has no linkname")
+                })?;
+                symlink(&target, dst)
+            }
+            EntryType::HardLink => {
+                let target = self.linkname().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "hardlink entry has no linkname")
+                })?;
+                fs::hard_link(&target, dst)
+            }
+            _ => {
+                let mut file = fs::File::create(dst)?;
+                io::copy(self, &mut file)?;
+                file.set_permissions(fs::Permissions::from_mode(self.mode()))
+            }
+        }
+    }
+}
+
+impl<'a, R: Read> Read for Entry<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &self.sparse {
+            Some(sparse) => self.read_sparse(sparse, buf),
+            None => self.read_dense(buf),
+        }
+    }
+}
+
+impl<'a, R: Read> Entry<'a, R> {
+    /// `Read` for a regular (non-sparse) entry: copies straight through
+    /// from the archive for exactly `remaining` bytes.
+    fn read_dense(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.remaining.get();
+        if remaining == 0 {
+            return Ok(0);
+        }
+        let max = cmp::min(remaining, buf.len() as u64) as usize;
+        let n = self.archive.read(&mut buf[..max])?;
+        self.remaining.set(remaining - n as u64);
+        Ok(n)
+    }
+
+    /// `Read` for a sparse entry: reconstructs the logical file from
+    /// `sparse`, filling holes (byte ranges `sparse.segments` doesn't
+    /// cover) with zeros and reading the rest straight through from the
+    /// archive, up to `sparse.realsize` bytes total.
+    fn read_sparse(&self, sparse: &SparseMap, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.logical_pos.get();
+        if pos >= sparse.realsize {
+            return Ok(0);
+        }
+
+        match sparse.segments.get(self.seg_idx.get()) {
+            Some(&(offset, _)) if pos < offset => {
+                let n = cmp::min(offset - pos, buf.len() as u64) as usize;
+                for b in &mut buf[..n] {
+                    *b = 0;
+                }
+                self.logical_pos.set(pos + n as u64);
+                Ok(n)
+            }
+            Some(&(offset, numbytes)) => {
+                let seg_end = offset + numbytes;
+                let max = cmp::min(seg_end - pos, buf.len() as u64) as usize;
+                let n = self.archive.read(&mut buf[..max])?;
+                self.logical_pos.set(pos + n as u64);
+                if pos + n as u64 == seg_end {
+                    self.seg_idx.set(self.seg_idx.get() + 1);
+                }
+                Ok(n)
+            }
+            None => {
+                let n = cmp::min(sparse.realsize - pos, buf.len() as u64) as usize;
+                for b in &mut buf[..n] {
+                    *b = 0;
+                }
+                self.logical_pos.set(pos + n as u64);
+                Ok(n)
+            }
+        }
+    }
+}
+
+fn is_zero_block(block: &[u8; 512]) -> bool {
+    block.iter().all(|&b| b == 0)
+}
+
+fn round_up_to_512(n: u64) -> u64 {
+    match n % 512 {
+        0 => n,
+        rem => n + (512 - rem),
+    }
+}
+
+fn trim_trailing_nul(bytes: &[u8]) -> &[u8] {
+    match bytes.iter().position(|&b| b == 0) {
+        Some(i) => &bytes[..i],
+        None => bytes,
+    }
+}
+
+/// A run of non-hole bytes in a sparse file, as reported by `SEEK_DATA`.
+struct SparseSegment {
+    offset: u64,
+    numbytes: u64,
+}
+
+const SEEK_DATA: i32 = 3;
+const SEEK_HOLE: i32 = 4;
+const ENXIO: i32 = 6;
+
+extern "C" {
+    fn lseek(fd: i32, offset: i64, whence: i32) -> i64;
+}
+
+fn is_sparse(segments: &[SparseSegment], file_len: u64) -> bool {
+    match segments {
+        [] => file_len > 0,
+        [single] => single.offset != 0 || single.numbytes != file_len,
+        _ => true,
+    }
+}
+
+/// Probes `file` for holes via `lseek(SEEK_HOLE)`/`SEEK_DATA`, returning
+/// the data segments found. Falls back to a single segment spanning the
+/// whole file if the filesystem doesn't support the `SEEK_DATA` extension.
+fn sparse_segments(file: &fs::File, file_len: u64) -> io::Result<Vec<SparseSegment>> {
+    let fd = file.as_raw_fd();
+    let mut segments = Vec::new();
+    let mut pos: i64 = 0;
+    while (pos as u64) < file_len {
+        let data_start = unsafe { lseek(fd, pos, SEEK_DATA) };
+        if data_start < 0 {
+            if io::Error::last_os_error().raw_os_error() == Some(ENXIO) {
+                break;
+            }
+            return Ok(vec![SparseSegment {
+                offset: 0,
+                numbytes: file_len,
+            }]);
+        }
+
+        let hole_start = unsafe { lseek(fd, data_start, SEEK_HOLE) };
+        let hole_start = if hole_start < 0 {
+            file_len as i64
+        } else {
+            hole_start
+        };
+        segments.push(SparseSegment {
+            offset: data_start as u64,
+            numbytes: (hole_start - data_start) as u64,
+        });
+        pos = hole_start;
+    }
+    Ok(segments)
+}
+
 unsafe fn cast<T, U>(a: &T) -> &U {
     assert_eq!(mem::size_of_val(a), mem::size_of::<U>());
     assert_eq!(mem::align_of_val(a), mem::align_of::<U>());
@@ -168,6 +1153,28 @@ unsafe fn cast_mut<T, U>(a: &mut T) -> &mut U {
     &mut *(a as *mut T as *mut U)
 }
 
+/// Returns `path` with a trailing `/` appended, unless it already ends
+/// with one, so directory entries are recognizable by their stored name.
+fn with_trailing_slash(path: &Path) -> PathBuf {
+    let mut bytes = path.as_os_str().as_bytes().to_vec();
+    if bytes.last() != Some(&b'/') {
+        bytes.push(b'/');
+    }
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+/// Finds the rightmost `/` that splits `path` into a `<= 155`-byte prefix
+/// and a `<= 100`-byte name, as required by the ustar header format.
+fn split_ustar_path(path: &[u8]) -> Option<(&[u8], &[u8])> {
+    let mut split_at = None;
+    for (i, &byte) in path.iter().enumerate() {
+        if byte == b'/' && i <= 155 && path.len() - i - 1 <= 100 {
+            split_at = Some(i);
+        }
+    }
+    split_at.map(|i| (&path[..i], &path[i + 1..]))
+}
+
 fn octal_into<T: fmt::Octal>(dst: &mut [u8], val: T) {
     let o = format!("{:o}", val);
     let value = o.bytes().rev().chain(repeat(b'0'));
@@ -176,9 +1183,465 @@ fn octal_into<T: fmt::Octal>(dst: &mut [u8], val: T) {
     }
 }
 
+/// Writes `val` as octal, falling back to the GNU/POSIX base-256
+/// extension when it doesn't fit in `dst.len() - 1` octal digits.
+fn numeric_into(dst: &mut [u8], val: u64) {
+    let max_octal = 8u64.pow(dst.len() as u32 - 1) - 1;
+    if val <= max_octal {
+        octal_into(dst, val);
+    } else {
+        base256_into(dst, val);
+    }
+}
+
+/// Reports whether `val` needs more than `field_len - 1` octal digits,
+/// i.e. whether `numeric_into` would fall back to base-256 for a field
+/// of that length.
+fn exceeds_octal(val: u64, field_len: u32) -> bool {
+    val > 8u64.pow(field_len - 1) - 1
+}
+
+/// Formats a single PAX extended-header record as `"<len> <key>=<value>\n"`,
+/// where `<len>` is the record's own total byte length, found by the
+/// usual fixed-point trick since the length field's width affects the
+/// total it describes.
+fn pax_record(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let fixed_len = key.len() + value.len() + 3; // ' ', '=', '\n'
+    let mut len = fixed_len + decimal_digits(fixed_len);
+    loop {
+        let total = fixed_len + decimal_digits(len);
+        if total == len {
+            break;
+        }
+        len = total;
+    }
+
+    let mut record = len.to_string().into_bytes();
+    record.push(b' ');
+    record.extend_from_slice(key);
+    record.push(b'=');
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+fn decimal_digits(mut n: usize) -> usize {
+    let mut digits = 1;
+    while n >= 10 {
+        n /= 10;
+        digits += 1;
+    }
+    digits
+}
+
+fn base256_into(dst: &mut [u8], val: u64) {
+    for slot in dst.iter_mut() {
+        *slot = 0;
+    }
+    let bytes = val.to_be_bytes();
+    let offset = dst.len() - bytes.len();
+    dst[offset..].copy_from_slice(&bytes);
+    dst[0] |= 0x80;
+}
+
 fn copy_into(slot: &mut [u8], bytes: &[u8]) -> io::Result<()> {
     for (slot, val) in slot.iter_mut().zip(bytes.iter().chain(Some(&0))) {
         *slot = *val;
     }
     Ok(())
 }
+
+/// Decodes a numeric field written by `numeric_into`: the GNU/POSIX
+/// base-256 extension when the high bit of the first byte is set,
+/// otherwise plain ASCII octal.
+fn octal_from(slot: &[u8]) -> u64 {
+    if slot[0] & 0x80 != 0 {
+        return base256_from(slot);
+    }
+    let digits: Vec<u8> = slot
+        .iter()
+        .cloned()
+        .skip_while(|&b| b == b' ' || b == 0)
+        .take_while(|&b| (b'0'..=b'7').contains(&b))
+        .collect();
+    if digits.is_empty() {
+        return 0;
+    }
+    u64::from_str_radix(str::from_utf8(&digits).unwrap(), 8).unwrap_or(0)
+}
+
+fn base256_from(slot: &[u8]) -> u64 {
+    let mut val: u64 = (slot[0] & 0x7f) as u64;
+    for &byte in &slot[1..] {
+        val = (val << 8) | byte as u64;
+    }
+    val
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    /// A `Write` sink backed by a `Rc<RefCell<Vec<u8>>>`, so the written
+    /// bytes can be inspected after the `Archiver` that owns it is dropped.
+    #[derive(Clone)]
+    struct SharedVec(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedVec {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("srstar-test-{}-{}", std::process::id(), name))
+    }
+
+    fn archive_with<F: FnOnce(&mut Archiver<SharedVec>)>(build: F) -> Vec<u8> {
+        let shared = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut archiver = Archiver::new(SharedVec(shared.clone()));
+            build(&mut archiver);
+        }
+        let bytes = shared.borrow().clone();
+        bytes
+    }
+
+    #[test]
+    fn gnu_long_name_round_trip() {
+        let fs_path = temp_path("gnu-long-name-src.txt");
+        fs::write(&fs_path, b"hello long name").unwrap();
+        let long_name = format!("dir/{}/file.txt", "segment".repeat(20));
+        assert!(long_name.len() > 100);
+
+        let buf = archive_with(|archiver| {
+            archiver.append_dir_all(&long_name, &fs_path).unwrap();
+        });
+
+        let archive = Archive::new(Cursor::new(buf));
+        let mut entries = archive.entries();
+        let mut entry = entries.next().unwrap().unwrap();
+        assert_eq!(entry.path(), PathBuf::from(&long_name));
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"hello long name");
+        assert!(entries.next().is_none());
+
+        fs::remove_file(&fs_path).unwrap();
+    }
+
+    #[test]
+    fn ustar_prefix_split_round_trip() {
+        let fs_path = temp_path("ustar-prefix-src.txt");
+        fs::write(&fs_path, b"prefix split").unwrap();
+        let long_name = format!("{}/{}", "p".repeat(120), "n".repeat(90));
+        assert!(long_name.len() > 100);
+
+        let buf = archive_with(|archiver| {
+            archiver.set_longname_mode(LongNameMode::Ustar);
+            archiver.append_dir_all(&long_name, &fs_path).unwrap();
+        });
+
+        let archive = Archive::new(Cursor::new(buf));
+        let entry = archive.entries().next().unwrap().unwrap();
+        assert_eq!(entry.path(), PathBuf::from(&long_name));
+        assert_eq!(&entry.header.as_ustar().magic, b"ustar\0");
+        assert_eq!(&entry.header.as_ustar().version, b"00");
+
+        fs::remove_file(&fs_path).unwrap();
+    }
+
+    #[test]
+    fn deterministic_mode_zeroes_identity_metadata() {
+        let fs_path = temp_path("deterministic-src.txt");
+        fs::write(&fs_path, b"reproducible").unwrap();
+
+        let buf = archive_with(|archiver| {
+            archiver.mode(HeaderMode::Deterministic);
+            archiver.add_file(&fs_path).unwrap();
+        });
+
+        let archive = Archive::new(Cursor::new(buf));
+        let entry = archive.entries().next().unwrap().unwrap();
+        assert_eq!(entry.mtime(), 0);
+        assert_eq!(entry.mode(), 0o644);
+
+        fs::remove_file(&fs_path).unwrap();
+    }
+
+    #[test]
+    fn deterministic_mode_is_byte_identical_across_runs() {
+        let fs_path = temp_path("deterministic-repeat-src.txt");
+        fs::write(&fs_path, b"same bytes every time").unwrap();
+
+        let build = || {
+            archive_with(|archiver| {
+                archiver.mode(HeaderMode::Deterministic);
+                archiver.add_file(&fs_path).unwrap();
+            })
+        };
+        assert_eq!(build(), build());
+
+        fs::remove_file(&fs_path).unwrap();
+    }
+
+    #[test]
+    fn base256_round_trip_for_oversized_values() {
+        let mut buf = [0u8; 12];
+        numeric_into(&mut buf, 9_000_000_000);
+        assert_eq!(buf[0] & 0x80, 0x80);
+        assert_eq!(octal_from(&buf), 9_000_000_000);
+    }
+
+    #[test]
+    fn octal_values_still_decode_without_base256_marker() {
+        let mut buf = [0u8; 12];
+        numeric_into(&mut buf, 0o755);
+        assert_eq!(buf[0] & 0x80, 0);
+        assert_eq!(octal_from(&buf), 0o755);
+    }
+
+    #[test]
+    fn is_sparse_detects_holes_but_not_full_single_segment_files() {
+        assert!(!is_sparse(&[SparseSegment { offset: 0, numbytes: 100 }], 100));
+        assert!(is_sparse(&[SparseSegment { offset: 0, numbytes: 50 }], 100));
+        assert!(is_sparse(&[], 100));
+        assert!(!is_sparse(&[], 0));
+        assert!(is_sparse(
+            &[
+                SparseSegment { offset: 0, numbytes: 10 },
+                SparseSegment { offset: 50, numbytes: 10 },
+            ],
+            100
+        ));
+    }
+
+    #[test]
+    fn sparse_file_round_trip_reconstructs_interior_holes() {
+        let fs_path = temp_path("sparse-data-src.bin");
+        fs::write(&fs_path, b"AAAA\0\0\0\0\0\0\0\0BBBB").unwrap();
+        let file = fs::File::open(&fs_path).unwrap();
+        let segments = vec![
+            SparseSegment { offset: 0, numbytes: 4 },
+            SparseSegment { offset: 12, numbytes: 4 },
+        ];
+        let logical_len = 16;
+
+        let buf = archive_with(|archiver| {
+            let header = archiver.name_header(Path::new("sparse.bin")).unwrap();
+            archiver.add_sparse_file(header, file, logical_len, &segments).unwrap();
+        });
+        let archive = Archive::new(Cursor::new(buf));
+        let mut entry = archive.entries().next().unwrap().unwrap();
+        assert_eq!(entry.entry_type(), EntryType::Other(b'S'));
+        assert_eq!(entry.size(), 16);
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"AAAA\0\0\0\0\0\0\0\0BBBB");
+
+        fs::remove_file(&fs_path).unwrap();
+    }
+
+    #[test]
+    fn unpack_reconstructs_sparse_file_with_real_hole() {
+        let fs_path = temp_path("sparse-real-src.bin");
+        let hole_len = 1_000_000u64;
+        {
+            let mut f = fs::File::create(&fs_path).unwrap();
+            f.write_all(b"head").unwrap();
+            f.seek(io::SeekFrom::Start(4 + hole_len)).unwrap();
+            f.write_all(b"tail").unwrap();
+        }
+
+        let buf = archive_with(|archiver| {
+            archiver.add_file(&fs_path).unwrap();
+        });
+
+        let archive = Archive::new(Cursor::new(buf));
+        let mut entry = archive.entries().next().unwrap().unwrap();
+        assert_eq!(entry.entry_type(), EntryType::Other(b'S'));
+
+        let dst_path = temp_path("sparse-real-dst.bin");
+        entry.unpack(&dst_path).unwrap();
+        let restored = fs::read(&dst_path).unwrap();
+        assert_eq!(restored.len(), 4 + hole_len as usize + 4);
+        assert_eq!(&restored[..4], b"head");
+        assert!(restored[4..4 + hole_len as usize].iter().all(|&b| b == 0));
+        assert_eq!(&restored[4 + hole_len as usize..], b"tail");
+
+        fs::remove_file(&fs_path).unwrap();
+        fs::remove_file(&dst_path).unwrap();
+    }
+
+    #[test]
+    fn symlink_hardlink_and_directory_round_trip() {
+        let dir_path = temp_path("entry-types-dir");
+        fs::create_dir_all(&dir_path).unwrap();
+        let file_path = dir_path.join("original.txt");
+        fs::write(&file_path, b"hardlinked content").unwrap();
+        let hardlink_path = dir_path.join("linked.txt");
+        fs::hard_link(&file_path, &hardlink_path).unwrap();
+        let symlink_path = dir_path.join("sym.txt");
+        symlink("original.txt", &symlink_path).unwrap();
+
+        let buf = archive_with(|archiver| {
+            archiver.add_dir(&dir_path).unwrap();
+            archiver.add_file(&file_path).unwrap();
+            archiver.add_file(&hardlink_path).unwrap();
+            archiver.add_symlink(&symlink_path).unwrap();
+        });
+
+        let archive = Archive::new(Cursor::new(buf));
+        let mut entries = archive.entries();
+
+        let dir_entry = entries.next().unwrap().unwrap();
+        assert_eq!(dir_entry.entry_type(), EntryType::Directory);
+
+        let file_entry = entries.next().unwrap().unwrap();
+        assert_eq!(file_entry.entry_type(), EntryType::Regular);
+
+        let hardlink_entry = entries.next().unwrap().unwrap();
+        assert_eq!(hardlink_entry.entry_type(), EntryType::HardLink);
+        assert_eq!(hardlink_entry.linkname(), Some(file_path.clone()));
+
+        let symlink_entry = entries.next().unwrap().unwrap();
+        assert_eq!(symlink_entry.entry_type(), EntryType::Symlink);
+        assert_eq!(symlink_entry.linkname(), Some(PathBuf::from("original.txt")));
+
+        fs::remove_dir_all(&dir_path).unwrap();
+    }
+
+    #[test]
+    fn long_symlink_target_round_trips_via_gnu_long_linkname() {
+        let dir_path = temp_path("long-linkname-dir");
+        fs::create_dir_all(&dir_path).unwrap();
+        let long_target = "t".repeat(150);
+        assert!(long_target.len() > 100);
+        let symlink_path = dir_path.join("sym.txt");
+        symlink(&long_target, &symlink_path).unwrap();
+
+        let buf = archive_with(|archiver| {
+            archiver.add_symlink(&symlink_path).unwrap();
+        });
+
+        let archive = Archive::new(Cursor::new(buf));
+        let entry = archive.entries().next().unwrap().unwrap();
+        assert_eq!(entry.entry_type(), EntryType::Symlink);
+        assert_eq!(entry.linkname(), Some(PathBuf::from(&long_target)));
+
+        fs::remove_dir_all(&dir_path).unwrap();
+    }
+
+    #[test]
+    fn long_hardlink_target_round_trips_in_pax_mode() {
+        let dir_path = temp_path("long-linkname-pax-dir");
+        fs::create_dir_all(&dir_path).unwrap();
+        let long_dir = dir_path.join("d".repeat(120));
+        fs::create_dir_all(&long_dir).unwrap();
+        let file_path = long_dir.join("original.txt");
+        fs::write(&file_path, b"long hardlink target").unwrap();
+        let hardlink_path = long_dir.join("linked.txt");
+        fs::hard_link(&file_path, &hardlink_path).unwrap();
+        assert!(file_path.as_os_str().len() > 100);
+
+        let buf = archive_with(|archiver| {
+            archiver.set_longname_mode(LongNameMode::Pax);
+            archiver.add_file(&file_path).unwrap();
+            archiver.add_file(&hardlink_path).unwrap();
+        });
+
+        let archive = Archive::new(Cursor::new(buf));
+        let mut entries = archive.entries();
+        entries.next().unwrap().unwrap();
+        let hardlink_entry = entries.next().unwrap().unwrap();
+        assert_eq!(hardlink_entry.entry_type(), EntryType::HardLink);
+        assert_eq!(hardlink_entry.linkname(), Some(file_path.clone()));
+
+        fs::remove_dir_all(&dir_path).unwrap();
+    }
+
+    /// Parses the PAX extended-header blob that `buf` is expected to start
+    /// with (typeflag `x`), returning its records.
+    fn leading_pax_records(buf: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let header = Header { bytes: buf[..512].try_into().unwrap() };
+        assert_eq!(header.as_gnu().typeflag[0], b'x');
+        let raw_size = octal_from(&header.as_gnu().size) as usize;
+        parse_pax_records(&buf[512..512 + raw_size])
+    }
+
+    #[test]
+    fn pax_size_record_uses_stored_length_not_logical_length_for_sparse_files() {
+        let fs_path = temp_path("pax-size-huge-sparse-src.bin");
+        fs::write(&fs_path, b"DATA").unwrap();
+        let huge_len = 8u64.pow(11) + 100; // overflows the 12-byte octal `size`/`realsize` field
+        fs::OpenOptions::new().write(true).open(&fs_path).unwrap().set_len(huge_len).unwrap();
+
+        let buf = archive_with(|archiver| {
+            archiver.add_file(&fs_path).unwrap();
+        });
+
+        // The file's logical length overflows the octal field this crate's
+        // own `queue_pax_size` guards, but the actual stored data (only the
+        // data segment holding `DATA`, as `SEEK_DATA`/`SEEK_HOLE` report it)
+        // doesn't, so no `size` PAX override should be queued for it.
+        let header = Header { bytes: buf[..512].try_into().unwrap() };
+        if header.as_gnu().typeflag[0] == b'x' {
+            let raw_size = octal_from(&header.as_gnu().size) as usize;
+            let records = parse_pax_records(&buf[512..512 + raw_size]);
+            assert!(!records.iter().any(|(k, _)| k == b"size"));
+        }
+
+        let archive = Archive::new(Cursor::new(buf));
+        let mut entry = archive.entries().next().unwrap().unwrap();
+        assert_eq!(entry.entry_type(), EntryType::Other(b'S'));
+        assert_eq!(entry.size(), huge_len);
+        let mut head = [0u8; 8];
+        entry.read_exact(&mut head).unwrap();
+        assert_eq!(&head, b"DATA\0\0\0\0");
+
+        fs::remove_file(&fs_path).unwrap();
+    }
+
+    #[test]
+    fn add_pax_extension_round_trips_custom_attribute() {
+        let fs_path = temp_path("pax-extension-src.txt");
+        fs::write(&fs_path, b"custom attribute").unwrap();
+
+        let buf = archive_with(|archiver| {
+            archiver.add_pax_extension("SCHILY.xattr.user.note", "hello");
+            archiver.add_file(&fs_path).unwrap();
+        });
+
+        let records = leading_pax_records(&buf);
+        assert!(records
+            .iter()
+            .any(|(k, v)| k == b"SCHILY.xattr.user.note" && v == b"hello"));
+
+        fs::remove_file(&fs_path).unwrap();
+    }
+
+    #[test]
+    fn complete_mode_emits_pax_mtime_for_directories() {
+        let dir_path = temp_path("pax-identity-dir");
+        fs::create_dir_all(&dir_path).unwrap();
+        let mtime = std::time::SystemTime::UNIX_EPOCH
+            + std::time::Duration::new(1_700_000_000, 123_000_000);
+        fs::File::open(&dir_path).unwrap().set_modified(mtime).unwrap();
+
+        let buf = archive_with(|archiver| {
+            archiver.add_dir(&dir_path).unwrap();
+        });
+
+        let records = leading_pax_records(&buf);
+        let mtime_record = records.iter().find(|(k, _)| k == b"mtime").expect("mtime PAX record");
+        assert_eq!(mtime_record.1, b"1700000000.123000000");
+
+        fs::remove_dir_all(&dir_path).unwrap();
+    }
+}